@@ -0,0 +1,108 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2020 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementation of the `sign` subcommand
+use sc_cli::{
+	Error, CliConfiguration, KeystoreParams, SharedParams,
+	decode_hex, with_crypto_scheme,
+	CryptoSchemeFlag,
+};
+use structopt::StructOpt;
+use codec::Encode;
+use std::io::Read;
+
+/// The `sign` command
+#[derive(Debug, StructOpt)]
+#[structopt(
+	name = "sign",
+	about = "Sign a message, with a given (secret) key. Reads the message from \
+	`--message`, or from stdin if not provided."
+)]
+pub struct SignCmd {
+	/// The message to sign. If not provided, the message is read from stdin.
+	#[structopt(long)]
+	message: Option<String>,
+
+	/// The message is hex-encoded data, rather than UTF-8 text.
+	#[structopt(long)]
+	hex: bool,
+
+	/// The secret key URI.
+	#[structopt(long)]
+	suri: String,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub keystore_params: KeystoreParams,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub crypto_scheme: CryptoSchemeFlag,
+}
+
+impl SignCmd {
+	/// Run the command
+	pub fn run(&self) -> Result<(), Error> {
+		let message = self.message()?;
+		let password = self.keystore_params.read_password()?;
+
+		with_crypto_scheme!(
+			self.crypto_scheme.scheme,
+			sign(&self.suri, password.as_ref().map(String::as_str), &message)
+		)
+	}
+
+	fn message(&self) -> Result<Vec<u8>, Error> {
+		let message = match &self.message {
+			Some(message) => message.clone(),
+			None => {
+				let mut message = String::new();
+				std::io::stdin().read_to_string(&mut message)?;
+				message
+			},
+		};
+		let message = message.trim();
+
+		if self.hex {
+			Ok(decode_hex(message)?)
+		} else {
+			Ok(message.as_bytes().to_vec())
+		}
+	}
+}
+
+impl CliConfiguration for SignCmd {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+
+	fn keystore_params(&self) -> Option<&KeystoreParams> {
+		Some(&self.keystore_params)
+	}
+}
+
+fn sign<Pair>(uri: &str, pass: Option<&str>, message: &[u8]) -> Result<(), Error>
+	where
+		Pair: sp_core::Pair,
+		Pair::Signature: Encode,
+{
+	crate::utils::sign_and_print::<Pair>(uri, pass, message)
+}
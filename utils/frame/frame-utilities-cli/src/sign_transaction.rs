@@ -24,37 +24,289 @@ use sc_cli::{
 use structopt::StructOpt;
 use codec::{Codec, Encode, Decode};
 use std::{str::FromStr, fmt::Debug};
-use sp_runtime::{MultiSigner, MultiSignature, AccountId32};
-use frame_utils::{SignedExtensionProvider, IndexFor, CallFor, AccountIdFor, AddressFor};
-use crate::utils::create_extrinsic_for;
+use sp_runtime::{MultiSigner, MultiSignature, AccountId32, generic::Era};
+use frame_utils::{
+	SignedExtensionProvider, IndexFor, CallFor, AccountIdFor, AddressFor, BalanceFor,
+	ExtraFor, AdditionalSignedFor,
+};
+use crate::utils::{create_signing_payload_for, assemble_extrinsic_for, create_sponsored_extrinsic_for};
 use sp_core::hexdisplay::HexDisplay;
 
 type Bytes = Vec<u8>;
 
-/// The `sign-transaction` command
+/// The `sign-transaction` command and its air-gapped subcommands.
 #[derive(Debug, StructOpt)]
 #[structopt(
 	name = "sign-transaction",
 	about = "Sign transaction from encoded Call.\
 	Returns a signed and encoded UncheckedMortalCompactExtrinsic as hex."
 )]
-pub struct SignTransactionCmd {
-	/// The secret key URI.
+pub enum SignTransactionCmd {
+	/// Build the raw SCALE-encoded signing payload for a call, without signing it.
+	/// Does not require access to a private key, so it can be run on a machine
+	/// that never sees the signer's key material.
+	NewPayload(NewPayloadCmd),
+	/// Sign a payload produced by `new-payload` with a SURI or keystore key,
+	/// emitting just the signature.
+	SignPayload(SignPayloadCmd),
+	/// Assemble a call, the `extra` emitted by `new-payload`, the signer's
+	/// address and a signature produced by `sign-payload` into the final
+	/// `UncheckedExtrinsic` hex.
+	Assemble(AssembleCmd),
+	/// Sign a call for a fee-sponsor (meta-transaction) flow: `--sponsor-suri`
+	/// signs the call, the fee parameters and the origin's address, and that
+	/// signature is embedded in the `extra` via the provider's fee-agent signed
+	/// extension; the origin then signs the call with that final `extra`, and
+	/// its signature is the extrinsic's own signature. The sponsor does not
+	/// sign the outer payload, and the origin's signature is not carried in a
+	/// signed extension.
+	Sponsor(SponsorCmd),
+}
+
+/// Common era/tip parameters shared by the commands that build a signing payload.
+#[derive(Debug, StructOpt)]
+pub struct EraParams {
+	/// Hash of the era-checkpoint block the signature is anchored on. For an
+	/// immortal transaction (the default, see `--period`) this is the genesis
+	/// hash.
+	#[structopt(long, parse(try_from_str = decode_hex))]
+	prior_block_hash: Bytes,
+
+	/// Era period, for mortal transactions. Rounded to a power of two in the
+	/// range `[4, 65536]`. A period of `0` (the default) produces an immortal
+	/// transaction that never expires.
+	#[structopt(long, default_value = "0")]
+	period: u64,
+
+	/// Block number at the start of the era, used together with `--period`
+	/// to compute the transaction's era. Its hash must be passed via
+	/// `--prior-block-hash`. Ignored for immortal transactions.
+	#[structopt(long, default_value = "0")]
+	checkpoint_block_number: u64,
+
+	/// Tip to include in the transaction, used to prioritise it for block
+	/// inclusion. Requires `--charge-transaction-payment` or `--asset-id` to be
+	/// set; a nonzero tip without either is rejected rather than silently dropped.
+	#[structopt(long, default_value = "0")]
+	tip: u128,
+
+	/// Include the `ChargeTransactionPayment` signed extension in the
+	/// constructed extrinsic, allowing `--tip` to take effect.
 	#[structopt(long)]
-	suri: String,
+	charge_transaction_payment: bool,
+
+	/// Pay transaction fees (and `--tip`, if set) in the given asset instead of
+	/// the chain's native token, via the `ChargeAssetTxPayment` signed
+	/// extension. Leave unset to pay fees in the native token.
+	#[structopt(long)]
+	asset_id: Option<u32>,
+}
+
+impl EraParams {
+	fn era(&self) -> Era {
+		if self.period == 0 {
+			Era::immortal()
+		} else {
+			Era::mortal(self.period, self.checkpoint_block_number)
+		}
+	}
+
+	/// Reject parameter combinations that would silently drop the tip: a
+	/// nonzero `--tip` only takes effect when either `--charge-transaction-payment`
+	/// or `--asset-id` is also set.
+	fn validate(&self) -> Result<(), Error> {
+		if self.tip != 0 && !self.charge_transaction_payment && self.asset_id.is_none() {
+			return Err(Error::Input(
+				"`--tip` has no effect without `--charge-transaction-payment` or `--asset-id`".into(),
+			));
+		}
+		Ok(())
+	}
 
+	fn tip<P: SignedExtensionProvider>(&self) -> Option<BalanceFor<P>>
+		where
+			BalanceFor<P>: From<u128>,
+	{
+		if self.charge_transaction_payment || self.asset_id.is_some() {
+			Some(BalanceFor::<P>::from(self.tip))
+		} else {
+			None
+		}
+	}
+}
+
+/// The `new-payload` subcommand.
+#[derive(Debug, StructOpt)]
+pub struct NewPayloadCmd {
 	/// The nonce.
 	#[structopt(long)]
 	nonce: GenericNumber,
 
-	/// genesis hash, for signed extensions.
+	/// The call, hex-encoded.
 	#[structopt(long, parse(try_from_str = decode_hex))]
-	prior_block_hash: Bytes,
+	call: Bytes,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub era_params: EraParams,
 
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+impl NewPayloadCmd {
+	/// Run the command
+	pub fn run<P>(&self) -> Result<(), Error>
+		where
+			P: SignedExtensionProvider + pallet_indices::Trait,
+			<IndexFor<P> as FromStr>::Err: Debug,
+			CallFor<P>: Codec,
+			BalanceFor<P>: From<u128>,
+			ExtraFor<P>: Encode,
+			AdditionalSignedFor<P>: Encode,
+	{
+		self.era_params.validate()?;
+		let nonce = self.nonce.parse::<IndexFor<P>>()?;
+		let hash = <P::Hash as Decode>::decode(&mut &self.era_params.prior_block_hash[..])?;
+		let call = CallFor::<P>::decode(&mut &self.call[..])?;
+
+		let signing_payload = create_signing_payload_for::<P, P::Call>(
+			call,
+			nonce,
+			self.era_params.era(),
+			hash,
+			self.era_params.tip::<P>(),
+			self.era_params.asset_id,
+		)?;
+		// `--extra` must be passed to `assemble` unchanged: it's what binds the
+		// final extrinsic to exactly the payload that gets signed here.
+		println!("payload: 0x{}", HexDisplay::from(&signing_payload.payload));
+		println!("extra: 0x{}", HexDisplay::from(&signing_payload.extra));
+		Ok(())
+	}
+}
+
+/// The `sign-payload` subcommand.
+#[derive(Debug, StructOpt)]
+pub struct SignPayloadCmd {
+	/// The secret key URI.
+	#[structopt(long)]
+	suri: String,
+
+	/// The signing payload produced by `new-payload`, hex-encoded.
+	#[structopt(long, parse(try_from_str = decode_hex))]
+	payload: Bytes,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub keystore_params: KeystoreParams,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub crypto_scheme: CryptoSchemeFlag,
+}
+
+impl SignPayloadCmd {
+	/// Run the command
+	pub fn run(&self) -> Result<(), Error> {
+		let password = self.keystore_params.read_password()?;
+
+		with_crypto_scheme!(
+			self.crypto_scheme.scheme,
+			sign_payload(&self.suri, password.as_ref().map(String::as_str), &self.payload)
+		)
+	}
+}
+
+/// The `assemble` subcommand.
+#[derive(Debug, StructOpt)]
+pub struct AssembleCmd {
 	/// The call, hex-encoded.
 	#[structopt(long, parse(try_from_str = decode_hex))]
 	call: Bytes,
 
+	/// SS58 address of the account that produced `--signature`.
+	#[structopt(long)]
+	account: AccountId32,
+
+	/// The `extra` emitted by `new-payload`, hex-encoded. Must come from the
+	/// same `new-payload` invocation that produced the payload `--signature`
+	/// was computed over — it is not re-derived from era/tip/nonce/asset-id
+	/// flags here, so the two can't silently disagree.
+	#[structopt(long, parse(try_from_str = decode_hex))]
+	extra: Bytes,
+
+	/// The signature over the payload produced by `new-payload`, as emitted by
+	/// `sign-payload`, hex-encoded.
+	#[structopt(long, parse(try_from_str = decode_hex))]
+	signature: Bytes,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub crypto_scheme: CryptoSchemeFlag,
+}
+
+impl AssembleCmd {
+	/// Run the command
+	pub fn run<P>(&self) -> Result<(), Error>
+		where
+			P: SignedExtensionProvider + pallet_indices::Trait,
+			AccountIdFor<P>: From<AccountId32>,
+			AddressFor<P>: From<AccountIdFor<P>> + Encode,
+			CallFor<P>: Codec,
+			ExtraFor<P>: Codec,
+	{
+		let call = CallFor::<P>::decode(&mut &self.call[..])?;
+		let extra = ExtraFor::<P>::decode(&mut &self.extra[..])?;
+		let address = AddressFor::<P>::from(AccountIdFor::<P>::from(self.account.clone()));
+
+		with_crypto_scheme!(
+			self.crypto_scheme.scheme,
+			assemble_ext::<P>(call, extra, address, &self.signature)
+		)
+	}
+}
+
+/// The `sponsor` subcommand.
+#[derive(Debug, StructOpt)]
+pub struct SponsorCmd {
+	/// The origin's secret key URI. The origin signs the outer payload: the
+	/// call and the final `extra`, which carries the sponsor's signature.
+	#[structopt(long)]
+	suri: String,
+
+	/// The sponsor's secret key URI. The sponsor signs the call, the fee
+	/// parameters and the origin's address, binding themselves as fee payer.
+	#[structopt(long)]
+	sponsor_suri: String,
+
+	/// Password to unlock `--sponsor-suri`, if it is encrypted. Independent of
+	/// `--keystore-params`' password, which only unlocks `--suri`: the origin
+	/// and sponsor keys may use different passwords.
+	#[structopt(long)]
+	sponsor_password: Option<String>,
+
+	/// The nonce.
+	#[structopt(long)]
+	nonce: GenericNumber,
+
+	/// The call, hex-encoded.
+	#[structopt(long, parse(try_from_str = decode_hex))]
+	call: Bytes,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub era_params: EraParams,
+
 	#[allow(missing_docs)]
 	#[structopt(flatten)]
 	pub keystore_params: KeystoreParams,
@@ -68,46 +320,108 @@ pub struct SignTransactionCmd {
 	pub crypto_scheme: CryptoSchemeFlag,
 }
 
-impl SignTransactionCmd {
+impl SponsorCmd {
 	/// Run the command
 	pub fn run<P>(&self) -> Result<(), Error>
 		where
 			P: SignedExtensionProvider + pallet_indices::Trait,
 			<IndexFor<P> as FromStr>::Err: Debug,
 			AccountIdFor<P>: From<AccountId32>,
-			AddressFor<P>: From<AccountIdFor<P>>,
-			CallFor<P>: Codec,
+			AddressFor<P>: From<AccountIdFor<P>> + Clone + Encode,
+			CallFor<P>: Codec + Clone,
+			BalanceFor<P>: From<u128> + Clone + Encode,
+			IndexFor<P>: Clone + Encode,
+			ExtraFor<P>: Encode,
+			AdditionalSignedFor<P>: Encode,
 	{
+		self.era_params.validate()?;
 		let nonce = self.nonce.parse::<IndexFor<P>>()?;
-		let hash = <P::Hash as Decode>::decode(&mut &self.prior_block_hash[..])?;
+		let hash = <P::Hash as Decode>::decode(&mut &self.era_params.prior_block_hash[..])?;
 		let call = CallFor::<P>::decode(&mut &self.call[..])?;
 		let password = self.keystore_params.read_password()?;
 
 		with_crypto_scheme!(
 			self.crypto_scheme.scheme,
-			print_ext<P>(&self.suri, password.as_ref().map(String::as_str), call, nonce, hash)
+			print_sponsored_ext::<P>(
+				&self.suri,
+				password.as_ref().map(String::as_str),
+				&self.sponsor_suri,
+				self.sponsor_password.as_ref().map(String::as_str),
+				call,
+				nonce,
+				self.era_params.era(),
+				hash,
+				self.era_params.tip::<P>(),
+				self.era_params.asset_id,
+			)
 		)
 	}
 }
 
+impl SignTransactionCmd {
+	/// Run the command
+	pub fn run<P>(&self) -> Result<(), Error>
+		where
+			P: SignedExtensionProvider + pallet_indices::Trait,
+			<IndexFor<P> as FromStr>::Err: Debug,
+			AccountIdFor<P>: From<AccountId32>,
+			AddressFor<P>: From<AccountIdFor<P>> + Clone + Encode,
+			CallFor<P>: Codec + Clone,
+			BalanceFor<P>: From<u128> + Clone + Encode,
+			IndexFor<P>: Clone + Encode,
+			ExtraFor<P>: Codec,
+			AdditionalSignedFor<P>: Encode,
+	{
+		match self {
+			SignTransactionCmd::NewPayload(cmd) => cmd.run::<P>(),
+			SignTransactionCmd::SignPayload(cmd) => cmd.run(),
+			SignTransactionCmd::Assemble(cmd) => cmd.run::<P>(),
+			SignTransactionCmd::Sponsor(cmd) => cmd.run::<P>(),
+		}
+	}
+}
 
 impl CliConfiguration for SignTransactionCmd {
 	fn shared_params(&self) -> &SharedParams {
-		&self.shared_params
+		match self {
+			SignTransactionCmd::NewPayload(cmd) => &cmd.shared_params,
+			SignTransactionCmd::SignPayload(cmd) => &cmd.shared_params,
+			SignTransactionCmd::Assemble(cmd) => &cmd.shared_params,
+			SignTransactionCmd::Sponsor(cmd) => &cmd.shared_params,
+		}
 	}
 
 	fn keystore_params(&self) -> Option<&KeystoreParams> {
-		Some(&self.keystore_params)
+		match self {
+			SignTransactionCmd::SignPayload(cmd) => Some(&cmd.keystore_params),
+			SignTransactionCmd::Sponsor(cmd) => Some(&cmd.keystore_params),
+			_ => None,
+		}
 	}
 }
 
+fn sign_payload<Pair>(uri: &str, pass: Option<&str>, payload: &[u8]) -> Result<(), Error>
+	where
+		Pair: sp_core::Pair,
+		Pair::Signature: Encode,
+{
+	// A `SignedPayload` hashes the payload with blake2_256 when it is longer
+	// than 256 bytes before signing (see `SignedPayload::using_encoded`); apply
+	// the same rule here so large calls produce a signature that verifies.
+	crate::utils::sign_and_print::<Pair>(uri, pass, &crate::utils::hash_if_large(payload))
+}
 
-fn print_ext<Pair, P>(
-	uri: &str,
-	pass: Option<&str>,
+fn print_sponsored_ext<Pair, P>(
+	origin_uri: &str,
+	origin_pass: Option<&str>,
+	sponsor_uri: &str,
+	sponsor_pass: Option<&str>,
 	call: CallFor<P>,
 	nonce: IndexFor<P>,
-	hash: P::Hash
+	era: Era,
+	hash: P::Hash,
+	tip: Option<BalanceFor<P>>,
+	asset_id: Option<u32>,
 ) -> Result<(), Error>
 	where
 		Pair: sp_core::Pair,
@@ -115,11 +429,61 @@ fn print_ext<Pair, P>(
 		Pair::Signature: Into<MultiSignature>,
 		P: SignedExtensionProvider + pallet_indices::Trait,
 		AccountIdFor<P>: From<AccountId32>,
-		AddressFor<P>: From<AccountIdFor<P>>,
+		AddressFor<P>: From<AccountIdFor<P>> + Clone + Encode,
+		CallFor<P>: Codec + Clone,
+		BalanceFor<P>: Clone + Encode,
+		IndexFor<P>: Clone + Encode,
+		ExtraFor<P>: Encode,
+		AdditionalSignedFor<P>: Encode,
+{
+	let origin = pair_from_suri::<Pair>(origin_uri, origin_pass);
+	let sponsor = pair_from_suri::<Pair>(sponsor_uri, sponsor_pass);
+	let extrinsic = create_sponsored_extrinsic_for::<Pair, P, P::Call>(
+		call, nonce, era, hash, tip, asset_id, origin, sponsor,
+	)?;
+	println!("0x{}", HexDisplay::from(&extrinsic.encode()));
+	Ok(())
+}
+
+fn assemble_ext<Pair, P>(
+	call: CallFor<P>,
+	extra: ExtraFor<P>,
+	address: AddressFor<P>,
+	signature: &[u8],
+) -> Result<(), Error>
+	where
+		Pair: sp_core::Pair,
+		Pair::Signature: Decode + Into<MultiSignature>,
+		P: SignedExtensionProvider + pallet_indices::Trait,
 		CallFor<P>: Codec,
+		AddressFor<P>: Encode,
+		ExtraFor<P>: Encode,
 {
-	let signer = pair_from_suri::<Pair>(uri, pass);
-	let extrinsic = create_extrinsic_for::<Pair, P, P::Call>(call, nonce, signer, hash)?;
+	let signature = <Pair::Signature as Decode>::decode(&mut &signature[..])?;
+	let extrinsic = assemble_extrinsic_for::<P, P::Call>(call, extra, address, signature.into())?;
 	println!("0x{}", HexDisplay::from(&extrinsic.encode()));
 	Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn era_params(period: u64, checkpoint_block_number: u64) -> EraParams {
+		EraParams {
+			prior_block_hash: vec![0u8; 32],
+			period,
+			checkpoint_block_number,
+			tip: 0,
+			charge_transaction_payment: false,
+			asset_id: None,
+		}
+	}
+
+	#[test]
+	fn era_is_immortal_iff_period_is_zero() {
+		assert_eq!(era_params(0, 0).era(), Era::immortal());
+		assert_eq!(era_params(0, 100).era(), Era::immortal());
+		assert_eq!(era_params(64, 100).era(), Era::mortal(64, 100));
+	}
+}